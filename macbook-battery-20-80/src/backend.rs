@@ -0,0 +1,306 @@
+use std::{process::Command, str};
+
+use crate::BoxedError;
+
+/// Where the laptop is currently drawing power from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerSource {
+    Ac,
+    Battery,
+}
+
+/// Raw charge/discharge reading used to estimate time remaining. Backends normalize units so
+/// that `remaining / present_rate` (or `(full - remaining) / present_rate` while charging)
+/// always yields seconds.
+#[derive(Debug, Clone, Copy)]
+pub struct ChargeState {
+    pub remaining: f64,
+    pub full: f64,
+    pub present_rate: f64,
+}
+
+/// Raw min/current/max charge levels for a single battery. `min` isn't always zero: some
+/// machines report a raw empty cutoff above zero, and design capacity vs last-full-capacity
+/// can skew what "full" means, so we normalize against the actual reported range instead of
+/// assuming 0..max.
+#[derive(Debug, Clone, Copy)]
+pub struct BatteryReading {
+    pub min: f64,
+    pub current: f64,
+    pub max: f64,
+}
+
+/// Aggregate readings from one or more batteries into a single percentage by summing each
+/// battery's remaining capacity and full capacity (each taken relative to its `min`, and
+/// using whatever `max` the backend reports), rather than averaging per-battery
+/// percentages, so a small and a large battery are weighted by their actual capacity.
+fn aggregate_charge_percent(readings: &[BatteryReading]) -> Result<i32, BoxedError> {
+    let mut remaining_sum = 0.0;
+    let mut full_sum = 0.0;
+
+    for reading in readings {
+        if reading.max <= reading.min {
+            continue;
+        }
+
+        remaining_sum += reading.current - reading.min;
+        full_sum += reading.max - reading.min;
+    }
+
+    if full_sum <= 0.0 {
+        return Err("No usable battery readings".into());
+    }
+
+    Ok((remaining_sum * 100.0 / full_sum).round() as i32)
+}
+
+/// A source of battery readings. Implementations hide how the reading is obtained
+/// (shelling out to a system utility, a cross-platform crate, ...) behind a small
+/// interface so the alerting logic above doesn't need to know or care.
+pub trait BatteryBackend {
+    /// Raw min/current/max readings for every battery in the system.
+    fn batteries(&self) -> Result<Vec<BatteryReading>, BoxedError>;
+
+    /// Current overall battery charge across all batteries, as a percentage from 0 to 100.
+    fn charge_percent(&self) -> Result<i32, BoxedError> {
+        aggregate_charge_percent(&self.batteries()?)
+    }
+
+    /// Whether the laptop is currently running off AC or battery power.
+    fn power_source(&self) -> Result<PowerSource, BoxedError>;
+
+    /// Raw charge/full-capacity/present-rate reading, used to estimate time remaining.
+    fn charge_state(&self) -> Result<ChargeState, BoxedError>;
+}
+
+/// Read a numeric field out of `ioreg -rc AppleSmartBattery`, once per battery instance
+/// reporting it (there's usually exactly one, but some machines have more than one battery).
+fn ioreg_fields(field: &str) -> Result<Vec<f64>, BoxedError> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!(
+            r#"ioreg -rc AppleSmartBattery | grep -E '"{}" = ' | sed -E 's/.*= //'"#,
+            field
+        ))
+        .output()?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "Command failed with status {}",
+            output.status.code().unwrap_or(-1)
+        )
+        .into());
+    }
+
+    let output_str = str::from_utf8(&output.stdout)?;
+
+    output_str
+        .lines()
+        .map(|line| line.trim().parse::<f64>().map_err(|e| e.into()))
+        .collect()
+}
+
+/// Backend built around the macOS `pmset` and `ioreg` CLIs: `pmset` for the power source,
+/// `ioreg` for capacity and current readings.
+pub struct PmsetBackend;
+
+impl BatteryBackend for PmsetBackend {
+    fn batteries(&self) -> Result<Vec<BatteryReading>, BoxedError> {
+        let current = ioreg_fields("CurrentCapacity")?;
+        let max = ioreg_fields("MaxCapacity")?;
+
+        if current.is_empty() || current.len() != max.len() {
+            return Err("Unexpected number of battery readings from ioreg".into());
+        }
+
+        Ok(current
+            .into_iter()
+            .zip(max)
+            .map(|(current, max)| BatteryReading {
+                min: 0.0,
+                current,
+                max,
+            })
+            .collect())
+    }
+
+    fn power_source(&self) -> Result<PowerSource, BoxedError> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(r#"pmset -g batt | sed -nE "s/Now drawing from '(.*)?'/\1/p""#)
+            .output()?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "Command failed with status {}",
+                output.status.code().unwrap_or(-1)
+            )
+            .into());
+        }
+
+        let output_str = str::from_utf8(&output.stdout)?.trim();
+
+        match output_str {
+            "AC Power" => Ok(PowerSource::Ac),
+            "Battery Power" => Ok(PowerSource::Battery),
+            _ => Err("Command contains unexpected output".into()),
+        }
+    }
+
+    fn charge_state(&self) -> Result<ChargeState, BoxedError> {
+        const SECONDS_PER_HOUR: f64 = 3600.0;
+
+        // ioreg reports capacity in mAh and current in mA, i.e. mAh / mA is hours. Scale the
+        // capacities up front so the caller can just divide by `present_rate` to get seconds.
+        // Summed across every battery instance, matching the aggregation `batteries()` does,
+        // so the percentage and the time estimate describe the same pack.
+        let current = ioreg_fields("CurrentCapacity")?;
+        let max = ioreg_fields("MaxCapacity")?;
+        let amperage = ioreg_fields("InstantAmperage")?;
+
+        if current.is_empty() || current.len() != max.len() || current.len() != amperage.len() {
+            return Err("Unexpected number of battery readings from ioreg".into());
+        }
+
+        Ok(ChargeState {
+            remaining: current.iter().sum::<f64>() * SECONDS_PER_HOUR,
+            full: max.iter().sum::<f64>() * SECONDS_PER_HOUR,
+            present_rate: amperage.iter().map(|amps| amps.abs()).sum(),
+        })
+    }
+}
+
+/// Cross-platform backend built on the `battery` crate, for Linux and Windows
+/// where `pmset` isn't available.
+pub struct CrossPlatformBackend {
+    manager: battery::Manager,
+}
+
+impl CrossPlatformBackend {
+    pub fn new() -> Result<Self, BoxedError> {
+        Ok(Self {
+            manager: battery::Manager::new()?,
+        })
+    }
+
+    fn first_battery(&self) -> Result<battery::Battery, BoxedError> {
+        self.manager
+            .batteries()?
+            .next()
+            .ok_or("No batteries found")?
+            .map_err(|e| e.into())
+    }
+}
+
+impl BatteryBackend for CrossPlatformBackend {
+    fn batteries(&self) -> Result<Vec<BatteryReading>, BoxedError> {
+        self.manager
+            .batteries()?
+            .map(|battery| {
+                let battery = battery?;
+
+                Ok(BatteryReading {
+                    min: 0.0,
+                    current: battery.energy().value as f64,
+                    max: battery.energy_full().value as f64,
+                })
+            })
+            .collect()
+    }
+
+    fn power_source(&self) -> Result<PowerSource, BoxedError> {
+        let battery = self.first_battery()?;
+
+        match battery.state() {
+            battery::State::Charging | battery::State::Full => Ok(PowerSource::Ac),
+            _ => Ok(PowerSource::Battery),
+        }
+    }
+
+    fn charge_state(&self) -> Result<ChargeState, BoxedError> {
+        // Energy is reported in Joules and power in Watts, and Joules / Watts is already
+        // seconds, so no scaling is needed here (unlike the mAh/mA reading on macOS). Summed
+        // across every battery, matching the aggregation `batteries()` does, so the percentage
+        // and the time estimate describe the same pack.
+        let mut remaining = 0.0;
+        let mut full = 0.0;
+        let mut present_rate = 0.0;
+
+        for battery in self.manager.batteries()? {
+            let battery = battery?;
+
+            remaining += battery.energy().value as f64;
+            full += battery.energy_full().value as f64;
+            present_rate += battery.energy_rate().value as f64;
+        }
+
+        Ok(ChargeState {
+            remaining,
+            full,
+            present_rate,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregate_charge_percent_weights_by_capacity_not_by_battery_count() {
+        let readings = [
+            BatteryReading {
+                min: 0.0,
+                current: 50.0,
+                max: 100.0,
+            },
+            BatteryReading {
+                min: 0.0,
+                current: 10.0,
+                max: 20.0,
+            },
+        ];
+
+        assert_eq!(aggregate_charge_percent(&readings).unwrap(), 50);
+    }
+
+    #[test]
+    fn aggregate_charge_percent_respects_a_nonzero_min() {
+        let readings = [BatteryReading {
+            min: 10.0,
+            current: 55.0,
+            max: 100.0,
+        }];
+
+        assert_eq!(aggregate_charge_percent(&readings).unwrap(), 50);
+    }
+
+    #[test]
+    fn aggregate_charge_percent_skips_readings_where_max_is_not_above_min() {
+        let readings = [
+            BatteryReading {
+                min: 10.0,
+                current: 5.0,
+                max: 10.0,
+            },
+            BatteryReading {
+                min: 0.0,
+                current: 25.0,
+                max: 100.0,
+            },
+        ];
+
+        assert_eq!(aggregate_charge_percent(&readings).unwrap(), 25);
+    }
+
+    #[test]
+    fn aggregate_charge_percent_errors_when_no_reading_is_usable() {
+        let readings = [BatteryReading {
+            min: 10.0,
+            current: 5.0,
+            max: 10.0,
+        }];
+
+        assert!(aggregate_charge_percent(&readings).is_err());
+    }
+}
@@ -1,56 +1,92 @@
+mod backend;
+
+use backend::{BatteryBackend, ChargeState, CrossPlatformBackend, PmsetBackend, PowerSource};
+use clap::{Parser, ValueEnum};
 use std::{
     process::{Command, Output},
-    str, thread,
+    thread,
     time::Duration,
 };
 
 type BoxedError = Box<dyn std::error::Error>;
 
-// We could make this a CLI setting
-/// Maximum maximum_interval in seconds to run the battery check
-/// This will usually happen when your battery level is 50%
-const MAX_INTERVAL_IN_SECONDS: i32 = 20 * 60;
-
-fn is_laptop_charging() -> Result<bool, BoxedError> {
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(r#"pmset -g batt | sed -nE "s/Now drawing from '(.*)?'/\1/p""#)
-        .output()?;
-
-    if !output.status.success() {
-        return Err(format!(
-            "Command failed with status {}",
-            output.status.code().unwrap_or(-1)
-        )
-        .into());
-    }
-
-    let output_str = str::from_utf8(&output.stdout)?.trim();
+/// How much progress output to print on each check.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Verbosity {
+    /// Print nothing besides the startup banner, errors and `--refresh` dry-run lines.
+    None,
+    /// Print alert lines ("Displaying ... alert") but not the per-loop status line.
+    Some,
+    /// Print everything, including the per-loop "Current battery level..." line.
+    Lots,
+}
 
-    match output_str {
-        "AC Power" => Ok(true),
-        "Battery Power" => Ok(false),
-        _ => Err("Command contains unexpected output".into()),
-    }
+/// How to pick the interval between battery checks.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum PollingMode {
+    /// Based purely on the static percentage, via a parabola centered between `low` and `high`.
+    Parabolic,
+    /// Based on the estimated time remaining until empty or full.
+    TimeBased,
 }
 
-fn get_battery_level() -> Result<i32, BoxedError> {
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg("pmset -g batt | grep -Eo '\\d+%' | cut -d% -f1")
-        .output()?;
+/// Watch the battery level and alert when it strays outside a healthy charge range.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Battery percentage at or below which the low charge alert is shown
+    #[arg(long, default_value_t = 20)]
+    low: i32,
+
+    /// Battery percentage at or below which the very-low charge alert is shown
+    #[arg(long = "very-low", default_value_t = 15)]
+    very_low: i32,
+
+    /// Battery percentage at or below which the critical charge alert (and optional suspend) is triggered
+    #[arg(long, default_value_t = 10)]
+    critical: i32,
+
+    /// Battery percentage at or above which the high charge alert is shown
+    #[arg(long, default_value_t = 80)]
+    high: i32,
+
+    /// Maximum interval in seconds between battery checks
+    #[arg(long = "max-interval", default_value_t = 20 * 60)]
+    max_interval: i32,
+
+    /// Dry-run mode: log what battery alert would be shown instead of displaying the blocking
+    /// dialog (the dialog for a failed battery reading is still shown)
+    #[arg(long)]
+    refresh: bool,
+
+    /// Suspend the system instead of just alerting once the battery reaches the critical level
+    #[arg(long = "suspend-on-critical")]
+    suspend_on_critical: bool,
+
+    /// Command to run to suspend the system when --suspend-on-critical fires outside of macOS,
+    /// e.g. "systemctl suspend". Defaults to "systemctl suspend"; ignored on macOS, which
+    /// always uses `pmset sleepnow`.
+    #[arg(long = "suspend-command")]
+    suspend_command: Option<String>,
+
+    /// How much progress output to print on each check
+    #[arg(long, value_enum, default_value_t = Verbosity::Some)]
+    verbosity: Verbosity,
+
+    /// How to pick the interval between battery checks
+    #[arg(long = "polling-mode", value_enum, default_value_t = PollingMode::Parabolic)]
+    polling_mode: PollingMode,
+}
 
-    if !output.status.success() {
-        return Err(format!(
-            "Command failed with status {}",
-            output.status.code().unwrap_or(-1)
-        )
-        .into());
+/// Select the battery backend to use on this platform. macOS uses `pmset` and `ioreg` directly
+/// since they're always available and give us everything we need; everywhere else we fall back to
+/// the cross-platform `battery` crate.
+fn select_backend() -> Result<Box<dyn BatteryBackend>, BoxedError> {
+    if cfg!(target_os = "macos") {
+        Ok(Box::new(PmsetBackend))
+    } else {
+        Ok(Box::new(CrossPlatformBackend::new()?))
     }
-
-    let output_str = str::from_utf8(&output.stdout)?;
-
-    output_str.trim().parse::<i32>().map_err(|e| e.into())
 }
 
 fn display_alert(title: &str, message: &str) -> Result<Output, std::io::Error> {
@@ -63,61 +99,282 @@ fn display_alert(title: &str, message: &str) -> Result<Output, std::io::Error> {
         .output()
 }
 
+/// Suspend the system. Uses `pmset sleepnow` on macOS; elsewhere runs `suspend_command`
+/// (falling back to `systemctl suspend`), split on whitespace into a program and its args.
+fn suspend_system(suspend_command: &Option<String>) -> Result<Output, std::io::Error> {
+    if cfg!(target_os = "macos") {
+        return Command::new("pmset").arg("sleepnow").output();
+    }
+
+    let command = suspend_command
+        .as_deref()
+        .unwrap_or("systemctl suspend");
+    let mut parts = command.split_whitespace();
+    let program = parts.next().unwrap_or("systemctl");
+
+    Command::new(program).args(parts).output()
+}
+
+/// Derive seconds remaining until empty (discharging) or full (charging) from a charge
+/// reading, or `None` when the present rate is zero or unknown rather than dividing by zero.
+fn estimate_seconds_remaining(charge: &ChargeState, power_source: PowerSource) -> Option<i32> {
+    if charge.present_rate == 0.0 {
+        return None;
+    }
+
+    let seconds = if power_source == PowerSource::Ac {
+        (charge.full - charge.remaining) / charge.present_rate
+    } else {
+        charge.remaining / charge.present_rate
+    };
+
+    if !seconds.is_finite() || seconds < 0.0 {
+        return None;
+    }
+
+    Some(seconds.round() as i32)
+}
+
+/// Format a duration in seconds as `H:MM`.
+fn format_hours_and_minutes(total_seconds: i32) -> String {
+    let total_minutes = total_seconds / 60;
+    format!("{}:{:02}", total_minutes / 60, total_minutes % 60)
+}
+
+/// One-shot latch per warning level, so each level only fires once until it is re-armed,
+/// mirroring how a battery daemon escalates and de-escalates. Critical re-arms once the battery
+/// climbs back above very-low, very-low once it climbs back above low, and low and high once
+/// the battery is strictly between `low` and `high`.
+struct AlertState {
+    low: bool,
+    very_low: bool,
+    critical: bool,
+    high: bool,
+}
+
+impl AlertState {
+    fn new() -> Self {
+        Self {
+            low: true,
+            very_low: true,
+            critical: true,
+            high: true,
+        }
+    }
+
+    fn rearm(&mut self, battery_level: i32, low: i32, very_low: i32, high: i32) {
+        if battery_level > very_low {
+            self.critical = true;
+        }
+        if battery_level > low {
+            self.very_low = true;
+        }
+        if battery_level > low && battery_level < high {
+            self.low = true;
+            self.high = true;
+        }
+    }
+}
+
 /// Calculate how much time to sleep depending on your current battery level
 /// This is calculated using a simple parabolic function (y=ax^2+bx+c). The sleep time is the highest
-/// when your battery level is 50%, and it's always 1 minute when you battery level is
-/// 20% and 80%. The interval varies more aggresively when you get closer to 20% and 80%,
-/// and it's relatively stable when you're close to 50%
-fn get_sleep_seconds(current_battery_level: i32, maximum_interval_in_seconds: i32) -> i32 {
+/// when your battery level is exactly between `low` and `high`, and it's always 1 minute when your
+/// battery level is at `low` or `high`. The interval varies more aggresively when you get closer to
+/// `low` and `high`, and it's relatively stable when you're close to the midpoint.
+fn get_sleep_seconds(
+    current_battery_level: i32,
+    low: i32,
+    high: i32,
+    maximum_interval_in_seconds: i32,
+) -> i32 {
     let maximum_interval_in_seconds = maximum_interval_in_seconds as f32;
-
-    let a = (60.0 - maximum_interval_in_seconds) / 900.0;
-    let b = (maximum_interval_in_seconds - 60.0) / 9.0;
-    let c = 4.0 / 9.0 * (375.0 - 4.0 * maximum_interval_in_seconds);
+    let mid = (low + high) as f32 / 2.0;
+    let half_range = (high - low) as f32 / 2.0;
 
     let x = current_battery_level as f32;
-    let y = a * x.powi(2) + b * x + c;
+    let y = maximum_interval_in_seconds
+        + (x - mid).powi(2) * (60.0 - maximum_interval_in_seconds) / half_range.powi(2);
 
     y.round() as i32
 }
 
+/// Calculate how much time to sleep based on the estimated time remaining until the battery
+/// hits empty (while discharging) or full (while charging), as returned by
+/// `estimate_seconds_remaining`. Far from the crossing we sleep up to `maximum_interval_in_seconds`;
+/// as the crossing approaches the interval shrinks toward a 60s floor, and we never sleep past
+/// the predicted crossing itself, so we always wake no later than when it happens. When the
+/// time remaining is unknown, we fall back to `maximum_interval_in_seconds`.
+fn get_sleep_seconds_time_based(
+    seconds_until_crossing: Option<i32>,
+    maximum_interval_in_seconds: i32,
+) -> i32 {
+    let Some(seconds_until_crossing) = seconds_until_crossing else {
+        return maximum_interval_in_seconds;
+    };
+
+    if seconds_until_crossing <= 0 {
+        return 60.min(maximum_interval_in_seconds);
+    }
+
+    (seconds_until_crossing / 2)
+        .max(60)
+        .min(maximum_interval_in_seconds)
+        .min(seconds_until_crossing)
+}
+
 /// Display the alert if the battery is at dangerous levels.
-/// The alert is only fired once, and will then wait until the battery gets to safe levels
-/// before attempting to trigger it again. We don't want the alert to be triggered non-stop
+/// Each level (critical, very-low, low, high) is only fired once, and will then wait until
+/// `AlertState::rearm` re-arms it before attempting to trigger it again. We don't want the
+/// alert to be triggered non-stop.
+///
+/// Takes `&Args` rather than its individual fields so that callers can't transpose two of the
+/// same-typed threshold values by passing them in the wrong positional order.
 fn display_alert_if_needed(
     battery_level: i32,
-    is_laptop_charging: &bool,
-    is_alert_allowed: &mut bool,
+    power_source: PowerSource,
+    time_remaining_seconds: Option<i32>,
+    alert_state: &mut AlertState,
+    args: &Args,
 ) -> Result<(), BoxedError> {
-    if *is_alert_allowed && !*is_laptop_charging && battery_level <= 20 {
-        println!("Displaying low charge alert");
+    let Args {
+        low,
+        very_low,
+        critical,
+        high,
+        suspend_on_critical,
+        ref suspend_command,
+        verbosity,
+        refresh: dry_run,
+        ..
+    } = *args;
+
+    let is_laptop_charging = power_source == PowerSource::Ac;
+    let time_remaining = time_remaining_seconds
+        .map(|seconds| format!(", ~{} remaining", format_hours_and_minutes(seconds)))
+        .unwrap_or_default();
+
+    if is_laptop_charging {
+        if alert_state.high && battery_level >= high {
+            if verbosity != Verbosity::None {
+                println!("Displaying high charge alert");
+            }
+
+            let message = format!(
+                "Battery is at {}%{}. Consider unplugging.",
+                battery_level, time_remaining
+            );
+            if dry_run {
+                println!("[dry-run] Would display alert 'Battery High': {}", message);
+            } else {
+                display_alert("Battery High", &message)?;
+            }
+
+            alert_state.high = false;
+        }
 
-        display_alert(
-            "Battery Low",
-            &format!("Battery is at {}%. Please charge it.", battery_level),
-        )?;
+        return Ok(());
+    }
 
-        *is_alert_allowed = false;
-    } else if *is_alert_allowed && *is_laptop_charging && battery_level >= 80 {
-        println!("Displaying high charge alert");
+    if alert_state.critical && battery_level <= critical {
+        if suspend_on_critical {
+            if verbosity != Verbosity::None {
+                println!("Battery critical, suspending the system");
+            }
 
-        display_alert(
-            "Battery High",
-            &format!("Battery is at {}%. Consider unplugging.", battery_level),
-        )?;
+            if dry_run {
+                println!(
+                    "[dry-run] Would suspend the system (battery at {}%)",
+                    battery_level
+                );
+            } else {
+                suspend_system(suspend_command)?;
+            }
+        } else {
+            if verbosity != Verbosity::None {
+                println!("Displaying critical charge alert");
+            }
+
+            let message = format!(
+                "Battery is at {}%{}. Please charge it immediately.",
+                battery_level, time_remaining
+            );
+            if dry_run {
+                println!(
+                    "[dry-run] Would display alert 'Battery Critical': {}",
+                    message
+                );
+            } else {
+                display_alert("Battery Critical", &message)?;
+            }
+        }
+
+        alert_state.critical = false;
+    } else if alert_state.very_low && battery_level <= very_low {
+        if verbosity != Verbosity::None {
+            println!("Displaying very low charge alert");
+        }
+
+        let message = format!(
+            "Battery is at {}%{}. Please charge it soon.",
+            battery_level, time_remaining
+        );
+        if dry_run {
+            println!(
+                "[dry-run] Would display alert 'Battery Very Low': {}",
+                message
+            );
+        } else {
+            display_alert("Battery Very Low", &message)?;
+        }
+
+        alert_state.very_low = false;
+    } else if alert_state.low && battery_level <= low {
+        if verbosity != Verbosity::None {
+            println!("Displaying low charge alert");
+        }
+
+        let message = format!(
+            "Battery is at {}%{}. Please charge it.",
+            battery_level, time_remaining
+        );
+        if dry_run {
+            println!("[dry-run] Would display alert 'Battery Low': {}", message);
+        } else {
+            display_alert("Battery Low", &message)?;
+        }
 
-        *is_alert_allowed = false;
+        alert_state.low = false;
     }
 
     Ok(())
 }
 
 fn main() {
-    println!("== MacBook battery 20%-80% running ==");
-    let mut is_alert_allowed = true;
+    let args = Args::parse();
+
+    if !(args.critical < args.very_low && args.very_low < args.low && args.low < args.high) {
+        eprintln!(
+            "Invalid thresholds: expected --critical ({}) < --very-low ({}) < --low ({}) < --high ({})",
+            args.critical, args.very_low, args.low, args.high
+        );
+        std::process::exit(1);
+    }
+
+    if args.max_interval <= 0 {
+        eprintln!(
+            "Invalid --max-interval ({}): must be greater than 0",
+            args.max_interval
+        );
+        std::process::exit(1);
+    }
+
+    println!("== MacBook battery {}%-{}% running ==", args.low, args.high);
+    let mut alert_state = AlertState::new();
+
+    let backend = select_backend().expect("Error selecting battery backend");
 
     loop {
-        let battery_level = match get_battery_level() {
+        let battery_level = match backend.charge_percent() {
             Ok(level) => level,
             Err(err) => {
                 eprintln!(
@@ -130,40 +387,58 @@ fn main() {
                 )
                 .expect("Error displaying alert");
 
-                thread::sleep(Duration::from_secs(MAX_INTERVAL_IN_SECONDS as u64));
+                thread::sleep(Duration::from_secs(args.max_interval as u64));
                 continue;
             }
         };
 
-        let is_laptop_charging = match is_laptop_charging() {
-            Ok(is_charging) => is_charging,
+        let power_source = match backend.power_source() {
+            Ok(source) => source,
             Err(err) => {
                 eprintln!(
                     "Error getting whether the laptop is charging. Error: {}",
                     err
                 );
 
-                // Assume it's not charging in case of error
-                false
+                // Assume it's running off battery in case of error
+                PowerSource::Battery
             }
         };
 
-        if !is_alert_allowed && (battery_level > 20 && battery_level < 80) {
-            is_alert_allowed = true;
-        }
+        let time_remaining_seconds = backend
+            .charge_state()
+            .ok()
+            .and_then(|charge| estimate_seconds_remaining(&charge, power_source));
 
-        if let Err(err) =
-            display_alert_if_needed(battery_level, &is_laptop_charging, &mut is_alert_allowed)
-        {
+        alert_state.rearm(battery_level, args.low, args.very_low, args.high);
+
+        if let Err(err) = display_alert_if_needed(
+            battery_level,
+            power_source,
+            time_remaining_seconds,
+            &mut alert_state,
+            &args,
+        ) {
             eprintln!("Error performing checks to display alert: {err}");
         }
 
-        let next_execution_in_seconds = get_sleep_seconds(battery_level, MAX_INTERVAL_IN_SECONDS);
+        let next_execution_in_seconds = match args.polling_mode {
+            PollingMode::Parabolic => {
+                get_sleep_seconds(battery_level, args.low, args.high, args.max_interval)
+            }
+            PollingMode::TimeBased => {
+                get_sleep_seconds_time_based(time_remaining_seconds, args.max_interval)
+            }
+        };
 
-        println!(
-            "Current battery level: {}%. Laptop charging: {}. Checking again in {} seconds.",
-            battery_level, is_laptop_charging, next_execution_in_seconds
-        );
+        if args.verbosity == Verbosity::Lots {
+            println!(
+                "Current battery level: {}%. Laptop charging: {}. Checking again in {} seconds.",
+                battery_level,
+                power_source == PowerSource::Ac,
+                next_execution_in_seconds
+            );
+        }
 
         thread::sleep(Duration::from_secs(next_execution_in_seconds as u64));
     }
@@ -181,7 +456,7 @@ mod tests {
 
         for maximum_interval in maximum_interval_in_seconds {
             println!("Testing maximum interval {}", maximum_interval);
-            let sleep_in_seconds = get_sleep_seconds(battery_level, maximum_interval);
+            let sleep_in_seconds = get_sleep_seconds(battery_level, 20, 80, maximum_interval);
             assert_eq!(sleep_in_seconds, expected_sleep_in_seconds);
         }
     }
@@ -194,7 +469,7 @@ mod tests {
 
         for maximum_interval in maximum_interval_in_seconds {
             println!("Testing maximum interval {}", maximum_interval);
-            let sleep_in_seconds = get_sleep_seconds(battery_level, maximum_interval);
+            let sleep_in_seconds = get_sleep_seconds(battery_level, 20, 80, maximum_interval);
             assert_eq!(sleep_in_seconds, expected_sleep_in_seconds);
         }
     }
@@ -206,8 +481,98 @@ mod tests {
 
         for maximum_interval in maximum_interval_in_seconds {
             println!("Testing maximum_interval {}", maximum_interval);
-            let sleep_in_seconds = get_sleep_seconds(battery_level, maximum_interval);
+            let sleep_in_seconds = get_sleep_seconds(battery_level, 20, 80, maximum_interval);
             assert_eq!(sleep_in_seconds, maximum_interval);
         }
     }
+
+    #[test]
+    fn estimate_seconds_remaining_is_none_when_present_rate_is_zero() {
+        let charge = ChargeState {
+            remaining: 50.0,
+            full: 100.0,
+            present_rate: 0.0,
+        };
+
+        assert_eq!(
+            estimate_seconds_remaining(&charge, PowerSource::Battery),
+            None
+        );
+    }
+
+    #[test]
+    fn estimate_seconds_remaining_while_discharging() {
+        let charge = ChargeState {
+            remaining: 3600.0,
+            full: 7200.0,
+            present_rate: 2.0,
+        };
+
+        assert_eq!(
+            estimate_seconds_remaining(&charge, PowerSource::Battery),
+            Some(1800)
+        );
+    }
+
+    #[test]
+    fn estimate_seconds_remaining_while_charging() {
+        let charge = ChargeState {
+            remaining: 3600.0,
+            full: 7200.0,
+            present_rate: 2.0,
+        };
+
+        assert_eq!(
+            estimate_seconds_remaining(&charge, PowerSource::Ac),
+            Some(1800)
+        );
+    }
+
+    #[test]
+    fn format_hours_and_minutes_pads_minutes() {
+        assert_eq!(format_hours_and_minutes(34 * 60), "0:34");
+        assert_eq!(format_hours_and_minutes(90 * 60), "1:30");
+    }
+
+    #[test]
+    fn get_sleep_seconds_time_based_is_maximum_interval_when_unknown() {
+        let maximum_interval_in_seconds = [0, 20, 50, 100, 200, 300];
+
+        for maximum_interval in maximum_interval_in_seconds {
+            println!("Testing maximum interval {}", maximum_interval);
+            let sleep_in_seconds = get_sleep_seconds_time_based(None, maximum_interval);
+            assert_eq!(sleep_in_seconds, maximum_interval);
+        }
+    }
+
+    #[test]
+    fn get_sleep_seconds_time_based_is_capped_at_maximum_interval_when_crossing_is_far_off() {
+        let maximum_interval_in_seconds = [0, 20, 50, 100, 200, 300];
+        let seconds_until_crossing = 10 * 60 * 60;
+
+        for maximum_interval in maximum_interval_in_seconds {
+            println!("Testing maximum interval {}", maximum_interval);
+            let sleep_in_seconds =
+                get_sleep_seconds_time_based(Some(seconds_until_crossing), maximum_interval);
+            assert_eq!(sleep_in_seconds, maximum_interval);
+        }
+    }
+
+    #[test]
+    fn get_sleep_seconds_time_based_never_sleeps_past_the_crossing() {
+        let seconds_until_crossing = [1, 30, 59, 90];
+
+        for seconds in seconds_until_crossing {
+            println!("Testing seconds until crossing {}", seconds);
+            let sleep_in_seconds = get_sleep_seconds_time_based(Some(seconds), 1200);
+            assert!(sleep_in_seconds <= seconds);
+        }
+    }
+
+    #[test]
+    fn get_sleep_seconds_time_based_shrinks_towards_60_as_crossing_approaches() {
+        assert_eq!(get_sleep_seconds_time_based(Some(3600), 1200), 1200);
+        assert_eq!(get_sleep_seconds_time_based(Some(240), 1200), 120);
+        assert_eq!(get_sleep_seconds_time_based(Some(100), 1200), 60);
+    }
 }